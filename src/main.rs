@@ -1,6 +1,10 @@
 #[cfg(feature = "dotenv")]
 use dotenvy::dotenv;
 
+mod queue;
+mod receive;
+mod settings;
+
 use libvoicetext_api::{self, ApiOptions, AudioFormat};
 use tracing_subscriber::{
     filter::LevelFilter, prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt,
@@ -10,13 +14,17 @@ use twilight_util::builder::embed::EmbedBuilder;
 
 use tracing_subscriber::Layer;
 
+use receive::Receiver;
 use songbird::{
+    driver::DecodeMode,
+    input::Input,
     shards::TwilightMap,
-    tracks::TrackHandle,
-    Songbird,
+    tracks::TrackQueue,
+    Config as SongbirdConfig, CoreEvent, Songbird,
 };
+use sqlx::sqlite::SqlitePool;
 use std::{
-    collections::HashMap, env, error::Error, num::NonZeroU64, sync::Arc,
+    collections::HashMap, env, error::Error, sync::Arc,
     time::Duration, ops::Deref,
 };
 use tokio::sync::{watch, RwLock};
@@ -38,9 +46,14 @@ use twilight_standby::Standby;
 #[derive(Debug)]
 struct StateRef {
     http: Arc<HttpClient>,
-    trackdata: RwLock<HashMap<Id<GuildMarker>, TrackHandle>>,
+    trackdata: RwLock<HashMap<Id<GuildMarker>, TrackQueue>>,
     songbird: Songbird,
     standby: Standby,
+    db: SqlitePool,
+    ssrc_map: RwLock<receive::SsrcMap>,
+    /// How many tracks ahead of the one currently playing are kept
+    /// pre-parsed at any given time. Configurable via `TTS_PREWARM_LOOKAHEAD`.
+    prewarm_lookahead: usize,
 }
 
 fn tracing_init() {
@@ -109,13 +122,24 @@ async fn main() -> anyhow::Result<()> {
 
     let senders = TwilightMap::new(HashMap::from([(shard.id().number(), shard.sender())]));
 
-    let songbird = Songbird::twilight(Arc::new(senders), user_id);
+    let songbird_config = SongbirdConfig::default().decode_mode(DecodeMode::Decode);
+    let songbird = Songbird::twilight_from_config(Arc::new(senders), user_id, songbird_config);
+
+    let db = settings::init(&env::var("DATABASE_URL")?).await?;
+
+    let prewarm_lookahead = env::var("TTS_PREWARM_LOOKAHEAD")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(2);
 
     let state = Arc::new(StateRef {
         http,
         trackdata: Default::default(),
         songbird,
         standby: Standby::new(),
+        db,
+        ssrc_map: Default::default(),
+        prewarm_lookahead,
     });
 
     // Since we only care about new messages, make the cache only
@@ -170,96 +194,334 @@ async fn handle_event(
         Event::Ready(ready) => {
             println!("Ready as: {}", ready.user.name);
         }
-        Event::MessageCreate(msg) => match msg.content.as_str() {
-            "!ping" => {
-                state
-                    .http
-                    .create_message(msg.channel_id)
-                    .content("Pong!")?
-                    .await?;
-            }
-            "!join" => {
-                let guild_id = msg.guild_id.ok_or("Can't join a non-guild channel.")?;
-                let channel_id = NonZeroU64::new(1001440920299905096)
-                    .ok_or("Joined voice channel must have nonzero ID.")?;
-
-                let content = match state.songbird.join(guild_id, channel_id).await {
-                    Ok(_handle) => format!("Joined <#{}>!", channel_id),
-                    Err(e) => format!("Failed to join <#{}>! Why: {:?}", channel_id, e),
-                };
-
-                state
-                    .http
-                    .create_message(msg.channel_id)
-                    .content(&content)?
-                    .await?;
-
-                let member = cache.guild_voice_states(guild_id);
-                tracing::trace!(?member);
-            }
-            "!leave" => {
-                state
-                    .http
-                    .create_message(msg.channel_id)
-                    .content("Pong!")?
-                    .await?;
-            }
-            "!test" => {
-                let response = libvoicetext_api::get_audio_data(
-                    env::var("VOICETEXT_API").unwrap(),
-                    ApiOptions {
-                        text: "テスト".to_owned(),
-                        format: Some(AudioFormat::Ogg),
-                        ..Default::default()
-                    },
-                    Duration::from_secs(1),
-                ).await;
-
-                match response {
-                    Ok(audio_data) => {
-                        state
-                            .http
-                            .create_message(msg.channel_id)
-                            .content("test!")?
-                            .attachments(&[Attachment::from_bytes(
-                                "test.ogg".to_owned(),
-                                audio_data.to_vec(),
-                                1,
-                            )])
-                            .unwrap()
-                            .await
-                            .unwrap();
-                    }
-                    Err(err) => {
-                        let error_embed = {
-                            let builder = EmbedBuilder::new().color(0xff0000);
-
-                            match err.status() {
-                                Some(status) => {
-                                    builder.title("APIリクエストエラー").description(format!(
-                                        "{}: {}",
-                                        status.as_u16(),
-                                        status
-                                            .canonical_reason()
-                                            .unwrap_or("<unknown status code>")
-                                    ))
+        Event::MessageCreate(msg) if msg.author.bot => {}
+        Event::MessageCreate(msg) => {
+            let mut words = msg.content.splitn(2, ' ');
+            let command = words.next().unwrap_or_default();
+            let arg = words.next().unwrap_or_default().trim();
+
+            match command {
+                "!ping" => {
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content("Pong!")?
+                        .await?;
+                }
+                "!join" => {
+                    let guild_id = msg.guild_id.ok_or("Can't join a non-guild channel.")?;
+
+                    let content = match cache.voice_state(msg.author.id, guild_id) {
+                        Some(voice_state) => {
+                            let channel_id = voice_state.channel_id();
+
+                            match state.songbird.join(guild_id, channel_id).await {
+                                Ok(call) => {
+                                    let mut handler = call.lock().await;
+                                    handler.add_global_event(
+                                        CoreEvent::SpeakingStateUpdate.into(),
+                                        Receiver::new(Arc::clone(&state), guild_id),
+                                    );
+                                    handler.add_global_event(
+                                        CoreEvent::VoiceTick.into(),
+                                        Receiver::new(Arc::clone(&state), guild_id),
+                                    );
+                                    handler.add_global_event(
+                                        CoreEvent::ClientDisconnect.into(),
+                                        Receiver::new(Arc::clone(&state), guild_id),
+                                    );
+                                    drop(handler);
+
+                                    format!("Joined <#{}>!", channel_id)
                                 }
-                                None => builder.title("APIのリクエストに失敗しました。")
-                            }.build()
-                        };
-
-                        state
-                            .http
-                            .create_message(msg.channel_id)
-                            .embeds(&[error_embed])?
-                            .await?;
+                                Err(e) => format!("Failed to join <#{}>! Why: {:?}", channel_id, e),
+                            }
+                        }
+                        None => "You need to be in a voice channel for me to join.".to_owned(),
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&content)?
+                        .await?;
+                }
+                "!leave" => {
+                    let guild_id = msg.guild_id.ok_or("Can't leave a non-guild channel.")?;
+
+                    let content = match state.songbird.remove(guild_id).await {
+                        Ok(()) => {
+                            state.trackdata.write().await.remove(&guild_id);
+                            state.ssrc_map.write().await.remove(&guild_id);
+                            "Left the voice channel.".to_owned()
+                        }
+                        Err(e) => format!("Failed to leave the voice channel! Why: {:?}", e),
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&content)?
+                        .await?;
+                }
+                "!skip" => {
+                    let guild_id = msg.guild_id.ok_or("Can't skip outside of a guild.")?;
+
+                    let content = match state.trackdata.read().await.get(&guild_id) {
+                        Some(queue) => {
+                            let _ = queue.skip();
+                            // The skipped track stays in the queue until its
+                            // end event is handled, so don't count it.
+                            format!(
+                                "Skipped. {} track(s) remaining.",
+                                queue.len().saturating_sub(1)
+                            )
+                        }
+                        None => "Nothing is playing.".to_owned(),
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&content)?
+                        .await?;
+                }
+                "!clear" => {
+                    let guild_id = msg.guild_id.ok_or("Can't clear outside of a guild.")?;
+
+                    let content = match state.trackdata.read().await.get(&guild_id) {
+                        Some(queue) => {
+                            queue.stop();
+                            "Cleared the queue."
+                        }
+                        None => "Nothing is playing.",
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(content)?
+                        .await?;
+                }
+                "!test" => {
+                    let response = libvoicetext_api::get_audio_data(
+                        env::var("VOICETEXT_API").unwrap(),
+                        ApiOptions {
+                            text: "テスト".to_owned(),
+                            format: Some(AudioFormat::Ogg),
+                            ..Default::default()
+                        },
+                        Duration::from_secs(1),
+                    ).await;
+
+                    match response {
+                        Ok(audio_data) => {
+                            state
+                                .http
+                                .create_message(msg.channel_id)
+                                .content("test!")?
+                                .attachments(&[Attachment::from_bytes(
+                                    "test.ogg".to_owned(),
+                                    audio_data.to_vec(),
+                                    1,
+                                )])
+                                .unwrap()
+                                .await
+                                .unwrap();
+                        }
+                        Err(err) => {
+                            let error_embed = {
+                                let builder = EmbedBuilder::new().color(0xff0000);
+
+                                match err.status() {
+                                    Some(status) => {
+                                        builder.title("APIリクエストエラー").description(format!(
+                                            "{}: {}",
+                                            status.as_u16(),
+                                            status
+                                                .canonical_reason()
+                                                .unwrap_or("<unknown status code>")
+                                        ))
+                                    }
+                                    None => builder.title("APIのリクエストに失敗しました。")
+                                }.build()
+                            };
+
+                            state
+                                .http
+                                .create_message(msg.channel_id)
+                                .embeds(&[error_embed])?
+                                .await?;
+                        }
                     }
                 }
+                "!voice" => {
+                    let guild_id = msg.guild_id.ok_or("Can't set a voice outside of a guild.")?;
+
+                    let content = if arg.is_empty() {
+                        "Speaker can't be empty.".to_owned()
+                    } else {
+                        settings::set_speaker(&state.db, guild_id, arg.to_owned()).await?;
+                        format!("Speaker set to `{}`.", arg)
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&content)?
+                        .await?;
+                }
+                "!speed" => {
+                    let guild_id = msg.guild_id.ok_or("Can't set a speed outside of a guild.")?;
+
+                    let content = match arg.parse::<i64>() {
+                        Ok(speed) if settings::SPEED_RANGE.contains(&speed) => {
+                            settings::set_speed(&state.db, guild_id, speed).await?;
+                            format!("Speed set to `{}`.", speed)
+                        }
+                        Ok(_) => format!(
+                            "Speed must be between {} and {}.",
+                            settings::SPEED_RANGE.start(),
+                            settings::SPEED_RANGE.end()
+                        ),
+                        Err(_) => "Speed must be a whole number.".to_owned(),
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&content)?
+                        .await?;
+                }
+                "!pitch" => {
+                    let guild_id = msg.guild_id.ok_or("Can't set a pitch outside of a guild.")?;
+
+                    let content = match arg.parse::<i64>() {
+                        Ok(pitch) if settings::PITCH_RANGE.contains(&pitch) => {
+                            settings::set_pitch(&state.db, guild_id, pitch).await?;
+                            format!("Pitch set to `{}`.", pitch)
+                        }
+                        Ok(_) => format!(
+                            "Pitch must be between {} and {}.",
+                            settings::PITCH_RANGE.start(),
+                            settings::PITCH_RANGE.end()
+                        ),
+                        Err(_) => "Pitch must be a whole number.".to_owned(),
+                    };
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&content)?
+                        .await?;
+                }
+                "!emotion" => {
+                    let guild_id = msg.guild_id.ok_or("Can't set an emotion outside of a guild.")?;
+
+                    let emotion = if arg.is_empty() { None } else { Some(arg.to_owned()) };
+                    settings::set_emotion(&state.db, guild_id, emotion.clone()).await?;
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .content(&format!("Emotion set to `{}`.", emotion.as_deref().unwrap_or("none")))?
+                        .await?;
+                }
+                "!settings" => {
+                    let guild_id = msg.guild_id.ok_or("Can't show settings outside of a guild.")?;
+                    let guild_settings = settings::get(&state.db, guild_id).await?;
+
+                    let embed = EmbedBuilder::new()
+                        .title("TTS voice settings")
+                        .description(format!(
+                            "speaker: `{}`\nspeed: `{}`\npitch: `{}`\nemotion: `{}`",
+                            guild_settings.speaker,
+                            guild_settings.speed,
+                            guild_settings.pitch,
+                            guild_settings.emotion.as_deref().unwrap_or("none"),
+                        ))
+                        .build();
+
+                    state
+                        .http
+                        .create_message(msg.channel_id)
+                        .embeds(&[embed])?
+                        .await?;
+                }
+                command if !command.starts_with('!') => {
+                    speak(&state, msg).await?;
+                }
+                _ => {}
             }
-            _ => {}
         }
         _ => {}
     }
 
     Ok(())
 }
+
+/// Synthesizes `msg`'s content and enqueues it onto the guild's [`TrackQueue`],
+/// so it plays once anything spoken ahead of it has finished. Does nothing if
+/// the bot hasn't joined a voice channel in the message's guild.
+async fn speak(
+    state: &Arc<StateRef>,
+    msg: &twilight_model::gateway::payload::incoming::MessageCreate,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(guild_id) = msg.guild_id else {
+        return Ok(());
+    };
+
+    let Some(call) = state.songbird.get(guild_id) else {
+        return Ok(());
+    };
+
+    let guild_settings = settings::get(&state.db, guild_id).await?;
+
+    let response = libvoicetext_api::get_audio_data(
+        env::var("VOICETEXT_API").unwrap(),
+        ApiOptions {
+            text: msg.content.clone(),
+            format: Some(AudioFormat::Ogg),
+            speaker: Some(guild_settings.speaker),
+            speed: Some(guild_settings.speed as u32),
+            pitch: Some(guild_settings.pitch as u32),
+            emotion: guild_settings.emotion,
+            ..Default::default()
+        },
+        Duration::from_secs(1),
+    )
+    .await;
+
+    let audio_data = match response {
+        Ok(audio_data) => audio_data,
+        Err(err) => {
+            tracing::warn!(?err, guild = %guild_id, "failed to synthesize message for TTS queue");
+            return Ok(());
+        }
+    };
+
+    let queue = {
+        let mut trackdata = state.trackdata.write().await;
+        trackdata.entry(guild_id).or_default().clone()
+    };
+
+    let input = Input::from(audio_data.to_vec());
+    let handle = queue.add_source(input, &mut *call.lock().await).await;
+
+    // Re-run the prewarm once this track ends, so whichever track slides
+    // into the lookahead window next gets warmed even if no new message
+    // arrives to trigger it.
+    if let Err(err) = handle.add_event(
+        songbird::Event::Track(songbird::TrackEvent::End),
+        queue::RewarmOnEnd {
+            queue: queue.clone(),
+            lookahead: state.prewarm_lookahead,
+        },
+    ) {
+        tracing::warn!(?err, "failed to register TTS queue prewarm handler");
+    }
+
+    queue::prewarm(&queue, state.prewarm_lookahead).await;
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! Keeps upcoming tracks in a guild's `TrackQueue` pre-parsed, so playback
+//! starts immediately once they reach the front instead of paying
+//! parse/seek latency right as the previous utterance ends.
+
+use std::time::Instant;
+
+use async_trait::async_trait;
+use songbird::{
+    tracks::TrackQueue, Event as SongbirdEvent, EventContext, EventHandler as VoiceEventHandler,
+};
+
+/// Eagerly drives the first `lookahead` tracks in `queue` through
+/// `make_playable`, so the driver doesn't have to do that work right as a
+/// track reaches the front. Cheap to call repeatedly: `make_playable_async`
+/// is a no-op on a track that's already playable.
+pub async fn prewarm(queue: &TrackQueue, lookahead: usize) {
+    // `current_queue()`'s first element is whatever's already playing, so
+    // skip it to warm the `lookahead` tracks actually still waiting.
+    for handle in queue.current_queue().into_iter().skip(1).take(lookahead) {
+        let prepare_started = Instant::now();
+
+        match handle.make_playable_async().await {
+            Ok(()) => tracing::debug!(
+                elapsed = ?prepare_started.elapsed(),
+                "prewarmed queued TTS track"
+            ),
+            Err(err) => tracing::warn!(?err, "failed to prewarm queued TTS track"),
+        }
+    }
+}
+
+/// Re-runs [`prewarm`] whenever a track finishes playing, so the track that
+/// just slid into the lookahead window gets warmed even though no new
+/// message triggered it.
+pub struct RewarmOnEnd {
+    pub queue: TrackQueue,
+    pub lookahead: usize,
+}
+
+#[async_trait]
+impl VoiceEventHandler for RewarmOnEnd {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<SongbirdEvent> {
+        prewarm(&self.queue, self.lookahead).await;
+        None
+    }
+}
@@ -0,0 +1,80 @@
+//! Tracks which cached Discord user each RTP SSRC in a guild's voice call
+//! belongs to, as groundwork for "now speaking" indicators/transcription.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use songbird::{Event as SongbirdEvent, EventContext, EventHandler as VoiceEventHandler};
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
+
+use crate::StateRef;
+
+/// Per-guild map from RTP SSRC to the Discord user speaking on it.
+pub type SsrcMap = HashMap<Id<GuildMarker>, HashMap<u32, Id<UserMarker>>>;
+
+/// Listens for `CoreEvent::SpeakingStateUpdate`, `CoreEvent::VoiceTick`, and
+/// `CoreEvent::ClientDisconnect` on a single guild's call, keeping
+/// [`StateRef::ssrc_map`] in sync with which user each SSRC belongs to.
+/// Entries are added on speaking-state updates and removed only when the
+/// user disconnects, not when they stop talking.
+pub struct Receiver {
+    state: Arc<StateRef>,
+    guild_id: Id<GuildMarker>,
+}
+
+impl Receiver {
+    pub fn new(state: Arc<StateRef>, guild_id: Id<GuildMarker>) -> Self {
+        Self { state, guild_id }
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for Receiver {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<SongbirdEvent> {
+        match ctx {
+            EventContext::SpeakingStateUpdate(update) => {
+                if let Some(user_id) = update.user_id {
+                    let user_id = Id::<UserMarker>::new(user_id.0);
+
+                    self.state
+                        .ssrc_map
+                        .write()
+                        .await
+                        .entry(self.guild_id)
+                        .or_default()
+                        .insert(update.ssrc, user_id);
+
+                    tracing::debug!(ssrc = update.ssrc, %user_id, "user started speaking");
+                }
+            }
+            EventContext::ClientDisconnect(disconnect) => {
+                let user_id = Id::<UserMarker>::new(disconnect.user_id.0);
+
+                if let Some(guild_map) = self.state.ssrc_map.write().await.get_mut(&self.guild_id) {
+                    guild_map.retain(|_, speaker| *speaker != user_id);
+                }
+
+                tracing::debug!(%user_id, "user disconnected from voice");
+            }
+            EventContext::VoiceTick(tick) => {
+                let ssrc_map = self.state.ssrc_map.read().await;
+                let Some(guild_map) = ssrc_map.get(&self.guild_id) else {
+                    return None;
+                };
+
+                for ssrc in tick.speaking.keys() {
+                    if let Some(user_id) = guild_map.get(ssrc) {
+                        tracing::trace!(%user_id, "user is speaking");
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
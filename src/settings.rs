@@ -0,0 +1,151 @@
+//! Per-guild TTS voice settings, persisted in a small SQLite database so they
+//! survive restarts.
+
+use std::ops::RangeInclusive;
+
+use sqlx::{sqlite::SqlitePool, FromRow};
+use twilight_model::id::{marker::GuildMarker, Id};
+
+/// Valid range for `ApiOptions::speed`, per the VoiceText API.
+pub const SPEED_RANGE: RangeInclusive<i64> = 50..=400;
+
+/// Valid range for `ApiOptions::pitch`, per the VoiceText API.
+pub const PITCH_RANGE: RangeInclusive<i64> = 50..=200;
+
+/// The speaker, speed, pitch, and emotion a guild wants its TTS played back
+/// with. Defaults match `ApiOptions::default()`.
+#[derive(Debug, Clone, FromRow)]
+pub struct GuildVoiceSettings {
+    pub speaker: String,
+    pub speed: i64,
+    pub pitch: i64,
+    pub emotion: Option<String>,
+}
+
+impl Default for GuildVoiceSettings {
+    fn default() -> Self {
+        Self {
+            speaker: "hikari".to_owned(),
+            speed: 100,
+            pitch: 100,
+            emotion: None,
+        }
+    }
+}
+
+/// Opens the settings database at `database_url`, creating the
+/// `guild_voice_settings` table if this is a fresh database.
+pub async fn init(database_url: &str) -> sqlx::Result<SqlitePool> {
+    let pool = SqlitePool::connect(database_url).await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS guild_voice_settings (
+            guild_id TEXT PRIMARY KEY,
+            speaker  TEXT NOT NULL,
+            speed    INTEGER NOT NULL,
+            pitch    INTEGER NOT NULL,
+            emotion  TEXT
+        )",
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(pool)
+}
+
+/// Loads `guild_id`'s settings, falling back to [`GuildVoiceSettings::default`]
+/// when the guild has no stored row.
+pub async fn get(pool: &SqlitePool, guild_id: Id<GuildMarker>) -> sqlx::Result<GuildVoiceSettings> {
+    let settings = sqlx::query_as::<_, GuildVoiceSettings>(
+        "SELECT speaker, speed, pitch, emotion FROM guild_voice_settings WHERE guild_id = ?",
+    )
+    .bind(guild_id.to_string())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(settings.unwrap_or_default())
+}
+
+pub async fn set_speaker(
+    pool: &SqlitePool,
+    guild_id: Id<GuildMarker>,
+    speaker: String,
+) -> sqlx::Result<()> {
+    let defaults = GuildVoiceSettings::default();
+
+    sqlx::query(
+        "INSERT INTO guild_voice_settings (guild_id, speaker, speed, pitch, emotion) \
+         VALUES (?, ?, ?, ?, ?) \
+         ON CONFLICT(guild_id) DO UPDATE SET speaker = excluded.speaker",
+    )
+    .bind(guild_id.to_string())
+    .bind(speaker)
+    .bind(defaults.speed)
+    .bind(defaults.pitch)
+    .bind(defaults.emotion)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_speed(pool: &SqlitePool, guild_id: Id<GuildMarker>, speed: i64) -> sqlx::Result<()> {
+    let defaults = GuildVoiceSettings::default();
+
+    sqlx::query(
+        "INSERT INTO guild_voice_settings (guild_id, speaker, speed, pitch, emotion) \
+         VALUES (?, ?, ?, ?, ?) \
+         ON CONFLICT(guild_id) DO UPDATE SET speed = excluded.speed",
+    )
+    .bind(guild_id.to_string())
+    .bind(defaults.speaker)
+    .bind(speed)
+    .bind(defaults.pitch)
+    .bind(defaults.emotion)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_pitch(pool: &SqlitePool, guild_id: Id<GuildMarker>, pitch: i64) -> sqlx::Result<()> {
+    let defaults = GuildVoiceSettings::default();
+
+    sqlx::query(
+        "INSERT INTO guild_voice_settings (guild_id, speaker, speed, pitch, emotion) \
+         VALUES (?, ?, ?, ?, ?) \
+         ON CONFLICT(guild_id) DO UPDATE SET pitch = excluded.pitch",
+    )
+    .bind(guild_id.to_string())
+    .bind(defaults.speaker)
+    .bind(defaults.speed)
+    .bind(pitch)
+    .bind(defaults.emotion)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_emotion(
+    pool: &SqlitePool,
+    guild_id: Id<GuildMarker>,
+    emotion: Option<String>,
+) -> sqlx::Result<()> {
+    let defaults = GuildVoiceSettings::default();
+
+    sqlx::query(
+        "INSERT INTO guild_voice_settings (guild_id, speaker, speed, pitch, emotion) \
+         VALUES (?, ?, ?, ?, ?) \
+         ON CONFLICT(guild_id) DO UPDATE SET emotion = excluded.emotion",
+    )
+    .bind(guild_id.to_string())
+    .bind(defaults.speaker)
+    .bind(defaults.speed)
+    .bind(defaults.pitch)
+    .bind(emotion)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}